@@ -0,0 +1,162 @@
+// Copyright 2022 CeresDB Project Authors. Licensed under Apache-2.0.
+
+use std::{
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
+
+use crate::{
+    db_client::{health::HealthMap, inner::InnerClient},
+    model::{
+        request::QueryRequest,
+        write::{WriteRequest, WriteResponse},
+        QueryResponse,
+    },
+    rpc_client::{retry, RpcClientFactory, RpcContext},
+    Error, Result,
+};
+
+/// A client layer above [`InnerClient`] that fails over to the next healthy
+/// endpoint, so a node currently in the [`HealthMap`] cooldown is skipped
+/// rather than retried.
+///
+/// This layer does not itself retry with backoff: each [`InnerClient`] call
+/// goes through [`RpcClientImpl`](crate::rpc_client::rpc_client_impl::RpcClientImpl),
+/// which already retries transient failures on the same endpoint up to
+/// `RpcOptions::max_retries` times. Applying a second retry-with-backoff
+/// loop here on top of that would let a single dead endpoint be hit
+/// `(max_retries + 1)` times per failover attempt instead of once, and
+/// double up the backoff delay. So `FailoverClient` makes exactly one pass
+/// over the healthy endpoints, moving on as soon as one reports a transient
+/// failure.
+pub(crate) struct FailoverClient<F: RpcClientFactory> {
+    clients: Vec<InnerClient<F>>,
+    health: HealthMap,
+    next: AtomicUsize,
+}
+
+impl<F: RpcClientFactory> FailoverClient<F> {
+    pub fn new(clients: Vec<InnerClient<F>>, unhealthy_cooldown: Duration) -> Self {
+        FailoverClient {
+            clients,
+            health: HealthMap::new(unhealthy_cooldown),
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    /// Exposed so a cluster/route layer built on top can avoid routing to
+    /// endpoints currently in cooldown.
+    pub(crate) fn health(&self) -> &HealthMap {
+        &self.health
+    }
+
+    pub async fn query(&self, ctx: &RpcContext, req: &QueryRequest) -> Result<QueryResponse> {
+        let mut last_err = None;
+
+        for _ in 0..self.clients.len() {
+            let Some(client) = self.pick_healthy().await else {
+                break;
+            };
+
+            match client.query_internal(ctx, req).await {
+                Ok(resp) => return Ok(resp),
+                Err(e) if retry::is_transient(&e) => {
+                    self.health.mark_unhealthy(client.endpoint()).await;
+                    last_err = Some(e);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+
+        Err(last_err.unwrap_or(Error::NoHealthyEndpoint))
+    }
+
+    pub async fn write(&self, ctx: &RpcContext, req: &WriteRequest) -> Result<WriteResponse> {
+        let mut last_err = None;
+
+        for _ in 0..self.clients.len() {
+            let Some(client) = self.pick_healthy().await else {
+                break;
+            };
+
+            match client.write_internal(ctx, req).await {
+                Ok(resp) => return Ok(resp),
+                Err(e) if retry::is_transient(&e) => {
+                    self.health.mark_unhealthy(client.endpoint()).await;
+                    last_err = Some(e);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+
+        Err(last_err.unwrap_or(Error::NoHealthyEndpoint))
+    }
+
+    /// Round-robins over the candidate endpoints, skipping any currently in
+    /// the [`HealthMap`] cooldown.
+    async fn pick_healthy(&self) -> Option<&InnerClient<F>> {
+        let start = self.next.fetch_add(1, Ordering::Relaxed);
+        for offset in 0..self.clients.len() {
+            let client = &self.clients[(start + offset) % self.clients.len()];
+            if self.health.is_healthy(client.endpoint()).await {
+                return Some(client);
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use async_trait::async_trait;
+
+    use super::*;
+    use crate::rpc_client::RpcClient;
+
+    struct FakeFactory;
+
+    #[async_trait]
+    impl RpcClientFactory for FakeFactory {
+        async fn build(&self, _endpoint: String) -> Result<Arc<dyn RpcClient>> {
+            unreachable!("these tests exercise endpoint selection, not dialing out")
+        }
+    }
+
+    fn failover(endpoints: &[&str]) -> FailoverClient<FakeFactory> {
+        let factory = Arc::new(FakeFactory);
+        FailoverClient::new(
+            endpoints
+                .iter()
+                .map(|e| InnerClient::new(factory.clone(), e.to_string()))
+                .collect(),
+            Duration::from_secs(30),
+        )
+    }
+
+    #[tokio::test]
+    async fn picks_the_first_endpoint_when_all_are_healthy() {
+        let fc = failover(&["a", "b", "c"]);
+        let picked = fc.pick_healthy().await.expect("at least one healthy endpoint");
+        assert_eq!(picked.endpoint(), "a");
+    }
+
+    #[tokio::test]
+    async fn skips_endpoints_in_the_health_cooldown() {
+        let fc = failover(&["a", "b", "c"]);
+        fc.health().mark_unhealthy("a").await;
+
+        let picked = fc.pick_healthy().await.expect("at least one healthy endpoint");
+        assert_ne!(picked.endpoint(), "a");
+    }
+
+    #[tokio::test]
+    async fn returns_none_when_every_endpoint_is_unhealthy() {
+        let fc = failover(&["a", "b"]);
+        fc.health().mark_unhealthy("a").await;
+        fc.health().mark_unhealthy("b").await;
+
+        assert!(fc.pick_healthy().await.is_none());
+    }
+}
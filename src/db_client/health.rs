@@ -0,0 +1,65 @@
+// Copyright 2022 CeresDB Project Authors. Licensed under Apache-2.0.
+
+use std::{collections::HashMap, time::Duration};
+
+use tokio::{sync::RwLock, time::Instant};
+
+/// Tracks endpoints that recently failed a request so the
+/// [`FailoverClient`](super::failover::FailoverClient) (or any future
+/// cluster/route layer built on top of it) can skip them for a cooldown
+/// period instead of repeatedly hitting a dead node.
+pub(crate) struct HealthMap {
+    cooldown: Duration,
+    unhealthy_until: RwLock<HashMap<String, Instant>>,
+}
+
+impl HealthMap {
+    pub fn new(cooldown: Duration) -> Self {
+        HealthMap {
+            cooldown,
+            unhealthy_until: RwLock::new(HashMap::new()),
+        }
+    }
+
+    pub async fn is_healthy(&self, endpoint: &str) -> bool {
+        match self.unhealthy_until.read().await.get(endpoint) {
+            Some(until) => Instant::now() >= *until,
+            None => true,
+        }
+    }
+
+    pub async fn mark_unhealthy(&self, endpoint: &str) {
+        self.unhealthy_until
+            .write()
+            .await
+            .insert(endpoint.to_string(), Instant::now() + self.cooldown);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn unmarked_endpoint_is_healthy() {
+        let health = HealthMap::new(Duration::from_secs(30));
+        assert!(health.is_healthy("127.0.0.1:1").await);
+    }
+
+    #[tokio::test]
+    async fn marked_endpoint_is_unhealthy_until_cooldown_expires() {
+        let health = HealthMap::new(Duration::from_millis(20));
+        health.mark_unhealthy("127.0.0.1:1").await;
+        assert!(!health.is_healthy("127.0.0.1:1").await);
+
+        tokio::time::sleep(Duration::from_millis(40)).await;
+        assert!(health.is_healthy("127.0.0.1:1").await);
+    }
+
+    #[tokio::test]
+    async fn marking_one_endpoint_does_not_affect_others() {
+        let health = HealthMap::new(Duration::from_secs(30));
+        health.mark_unhealthy("127.0.0.1:1").await;
+        assert!(health.is_healthy("127.0.0.1:2").await);
+    }
+}
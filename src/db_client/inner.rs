@@ -2,6 +2,7 @@
 
 use std::sync::Arc;
 
+use futures::stream::{Stream, StreamExt};
 use tokio::sync::OnceCell;
 
 use crate::{
@@ -32,6 +33,14 @@ impl<F: RpcClientFactory> InnerClient<F> {
         }
     }
 
+    /// The endpoint this client talks to, used by layers above
+    /// [`InnerClient`] (e.g. the quorum client) to report per-endpoint
+    /// failures.
+    #[inline]
+    pub(crate) fn endpoint(&self) -> &str {
+        &self.endpoint
+    }
+
     #[inline]
     async fn init(&self) -> Result<Arc<dyn RpcClient>> {
         self.factory.build(self.endpoint.clone()).await
@@ -51,6 +60,24 @@ impl<F: RpcClientFactory> InnerClient<F> {
             .and_then(QueryResponse::try_from)
     }
 
+    /// Streams query results page-by-page instead of buffering the whole
+    /// response, for analytic scans whose result set is too large to hold
+    /// in memory at once.
+    pub async fn query_stream_internal(
+        &self,
+        ctx: &RpcContext,
+        req: &QueryRequest,
+    ) -> Result<impl Stream<Item = Result<QueryResponse>>> {
+        let client_handle = self.inner_client.get_or_try_init(|| self.init()).await?;
+
+        let stream = client_handle
+            .as_ref()
+            .query_stream(ctx, req.clone().into())
+            .await?;
+
+        Ok(stream.map(|chunk| chunk.and_then(QueryResponse::try_from)))
+    }
+
     pub async fn write_internal(
         &self,
         ctx: &RpcContext,
@@ -0,0 +1,91 @@
+// Copyright 2022 CeresDB Project Authors. Licensed under Apache-2.0.
+
+mod failover;
+mod health;
+mod inner;
+mod quorum;
+
+use std::{sync::Arc, time::Duration};
+
+pub use failover::FailoverClient;
+pub use quorum::{EndpointFailure, QuorumClient, RequestStrategy};
+
+use inner::InnerClient;
+
+use crate::{
+    model::{
+        request::QueryRequest,
+        write::{WriteRequest, WriteResponse},
+        QueryResponse,
+    },
+    rpc_client::{RpcClientFactory, RpcContext},
+    Result,
+};
+
+/// How a [`DbClient`] distributes requests across its configured endpoints.
+pub enum ClientMode {
+    /// Talk to a single endpoint directly; the default for a single-node
+    /// deployment.
+    Standalone,
+    /// Round-robin across endpoints, skipping any currently in the
+    /// unhealthy cooldown rather than retrying a node that just failed.
+    Failover { unhealthy_cooldown: Duration },
+    /// Fan every request out to all endpoints and succeed once
+    /// `strategy.quorum` of them succeed.
+    Quorum { strategy: RequestStrategy },
+}
+
+/// The top-level client, dispatching to [`InnerClient`], [`FailoverClient`],
+/// or [`QuorumClient`] depending on the configured [`ClientMode`].
+pub(crate) enum DbClient<F: RpcClientFactory> {
+    Standalone(InnerClient<F>),
+    Failover(FailoverClient<F>),
+    Quorum(QuorumClient<F>),
+}
+
+impl<F: RpcClientFactory> DbClient<F> {
+    /// Builds a client talking to `endpoints` according to `mode`.
+    /// `endpoints` must be non-empty; [`ClientMode::Standalone`] uses only
+    /// the first one.
+    pub fn new(factory: Arc<F>, endpoints: Vec<String>, mode: ClientMode) -> Self {
+        match mode {
+            ClientMode::Standalone => {
+                let endpoint = endpoints
+                    .into_iter()
+                    .next()
+                    .expect("standalone mode requires at least one endpoint");
+                DbClient::Standalone(InnerClient::new(factory, endpoint))
+            }
+            ClientMode::Failover { unhealthy_cooldown } => {
+                let clients = endpoints
+                    .into_iter()
+                    .map(|endpoint| InnerClient::new(factory.clone(), endpoint))
+                    .collect();
+                DbClient::Failover(FailoverClient::new(clients, unhealthy_cooldown))
+            }
+            ClientMode::Quorum { strategy } => {
+                let clients = endpoints
+                    .into_iter()
+                    .map(|endpoint| InnerClient::new(factory.clone(), endpoint))
+                    .collect();
+                DbClient::Quorum(QuorumClient::new(clients, strategy))
+            }
+        }
+    }
+
+    pub async fn query(&self, ctx: &RpcContext, req: &QueryRequest) -> Result<QueryResponse> {
+        match self {
+            DbClient::Standalone(client) => client.query_internal(ctx, req).await,
+            DbClient::Failover(client) => client.query(ctx, req).await,
+            DbClient::Quorum(client) => client.query(ctx, req).await,
+        }
+    }
+
+    pub async fn write(&self, ctx: &RpcContext, req: &WriteRequest) -> Result<WriteResponse> {
+        match self {
+            DbClient::Standalone(client) => client.write_internal(ctx, req).await,
+            DbClient::Failover(client) => client.write(ctx, req).await,
+            DbClient::Quorum(client) => client.write(ctx, req).await,
+        }
+    }
+}
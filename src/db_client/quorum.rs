@@ -0,0 +1,216 @@
+// Copyright 2022 CeresDB Project Authors. Licensed under Apache-2.0.
+
+use std::{sync::Arc, time::Duration};
+
+use futures::stream::{FuturesUnordered, StreamExt};
+
+use crate::{
+    db_client::inner::InnerClient,
+    model::{
+        request::QueryRequest,
+        write::{WriteRequest, WriteResponse},
+        QueryResponse,
+    },
+    rpc_client::{RpcClientFactory, RpcContext},
+    Error, Result,
+};
+
+/// Strategy controlling how a request is fanned out across several
+/// endpoints and when the caller gets an answer back.
+#[derive(Clone, Debug)]
+pub struct RequestStrategy {
+    /// Per-endpoint timeout, applied the same way as [`RpcContext::timeout`].
+    pub timeout: Duration,
+    /// Number of endpoints that must succeed before the request is
+    /// considered successful.
+    pub quorum: usize,
+    /// Whether to drop the still in-flight futures as soon as quorum is
+    /// reached, instead of letting them run to completion in the
+    /// background.
+    pub interrupt_after_quorum: bool,
+}
+
+/// One endpoint's contribution to a failed quorum request.
+#[derive(Debug)]
+pub struct EndpointFailure {
+    pub endpoint: String,
+    pub source: Error,
+}
+
+/// A client layer above [`InnerClient`] that fans a request out to several
+/// endpoints and returns as soon as `quorum` of them have succeeded.
+pub(crate) struct QuorumClient<F: RpcClientFactory> {
+    clients: Vec<Arc<InnerClient<F>>>,
+    strategy: RequestStrategy,
+}
+
+impl<F: RpcClientFactory> QuorumClient<F> {
+    pub fn new(clients: Vec<InnerClient<F>>, strategy: RequestStrategy) -> Self {
+        QuorumClient {
+            clients: clients.into_iter().map(Arc::new).collect(),
+            strategy,
+        }
+    }
+
+    pub async fn query(&self, ctx: &RpcContext, req: &QueryRequest) -> Result<QueryResponse> {
+        let ctx = self.with_strategy_timeout(ctx);
+
+        let in_flight: FuturesUnordered<_> = self
+            .clients
+            .iter()
+            .cloned()
+            .map(|client| {
+                let ctx = ctx.clone();
+                let req = req.clone();
+                async move {
+                    let endpoint = client.endpoint().to_string();
+                    (endpoint, client.query_internal(&ctx, &req).await)
+                }
+            })
+            .collect();
+
+        self.quorum_loop(in_flight).await
+    }
+
+    pub async fn write(&self, ctx: &RpcContext, req: &WriteRequest) -> Result<WriteResponse> {
+        let ctx = self.with_strategy_timeout(ctx);
+
+        let in_flight: FuturesUnordered<_> = self
+            .clients
+            .iter()
+            .cloned()
+            .map(|client| {
+                let ctx = ctx.clone();
+                let req = req.clone();
+                async move {
+                    let endpoint = client.endpoint().to_string();
+                    (endpoint, client.write_internal(&ctx, &req).await)
+                }
+            })
+            .collect();
+
+        self.quorum_loop(in_flight).await
+    }
+
+    fn with_strategy_timeout(&self, ctx: &RpcContext) -> RpcContext {
+        let mut ctx = ctx.clone();
+        ctx.timeout = Some(self.strategy.timeout);
+        ctx
+    }
+
+    /// Drain `in_flight`, collecting successes until quorum is reached. When
+    /// `interrupt_after_quorum` is set the remaining futures are dropped
+    /// (and therefore cancelled) as soon as quorum is met. Otherwise,
+    /// ownership of the still-running futures is handed off to a background
+    /// task so they keep running to completion instead of being silently
+    /// cancelled the moment `in_flight` would go out of scope here.
+    async fn quorum_loop<T: Send + 'static>(
+        &self,
+        mut in_flight: FuturesUnordered<
+            impl std::future::Future<Output = (String, Result<T>)> + Send + 'static,
+        >,
+    ) -> Result<T> {
+        let mut results = Vec::with_capacity(self.strategy.quorum);
+        let mut failures = Vec::new();
+
+        while let Some((endpoint, result)) = in_flight.next().await {
+            match result {
+                Ok(resp) => {
+                    results.push(resp);
+                    if results.len() >= self.strategy.quorum {
+                        if !self.strategy.interrupt_after_quorum {
+                            tokio::spawn(async move { while in_flight.next().await.is_some() {} });
+                        }
+                        return Ok(results.swap_remove(0));
+                    }
+                }
+                Err(source) => failures.push(EndpointFailure { endpoint, source }),
+            }
+        }
+
+        Err(Error::Quorum {
+            quorum: self.strategy.quorum,
+            succeeded: results.len(),
+            failures,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use async_trait::async_trait;
+    use tonic::Status;
+
+    use super::*;
+    use crate::rpc_client::RpcClient;
+
+    struct FakeFactory;
+
+    #[async_trait]
+    impl RpcClientFactory for FakeFactory {
+        async fn build(&self, _endpoint: String) -> Result<Arc<dyn RpcClient>> {
+            unreachable!("these tests drive quorum_loop directly, without dialing out")
+        }
+    }
+
+    fn quorum_client(strategy: RequestStrategy) -> QuorumClient<FakeFactory> {
+        QuorumClient::new(vec![], strategy)
+    }
+
+    fn strategy(quorum: usize, interrupt_after_quorum: bool) -> RequestStrategy {
+        RequestStrategy {
+            timeout: Duration::from_secs(1),
+            quorum,
+            interrupt_after_quorum,
+        }
+    }
+
+    fn ok_after(endpoint: &str, value: i32) -> impl std::future::Future<Output = (String, Result<i32>)> {
+        let endpoint = endpoint.to_string();
+        async move { (endpoint, Ok(value)) }
+    }
+
+    fn err_after(endpoint: &str) -> impl std::future::Future<Output = (String, Result<i32>)> {
+        let endpoint = endpoint.to_string();
+        async move { (endpoint, Err(Error::Rpc(Status::unavailable("down")))) }
+    }
+
+    #[tokio::test]
+    async fn returns_as_soon_as_quorum_of_successes_is_reached() {
+        let client = quorum_client(strategy(2, true));
+        let in_flight: FuturesUnordered<_> = vec![ok_after("a", 1), ok_after("b", 2), ok_after("c", 3)]
+            .into_iter()
+            .collect();
+
+        let result = client.quorum_loop(in_flight).await;
+        assert!(result.is_ok());
+    }
+
+    #[tokio::test]
+    async fn fails_with_all_failures_when_quorum_is_unreachable() {
+        let client = quorum_client(strategy(2, true));
+        let in_flight: FuturesUnordered<_> = vec![err_after("a"), err_after("b")].into_iter().collect();
+
+        match client.quorum_loop(in_flight).await {
+            Err(Error::Quorum {
+                quorum,
+                succeeded,
+                failures,
+            }) => {
+                assert_eq!(quorum, 2);
+                assert_eq!(succeeded, 0);
+                assert_eq!(failures.len(), 2);
+            }
+            other => panic!("expected Error::Quorum, got {other:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn non_interrupting_strategy_still_returns_once_quorum_is_met() {
+        let client = quorum_client(strategy(1, false));
+        let in_flight: FuturesUnordered<_> = vec![ok_after("a", 1), ok_after("b", 2)].into_iter().collect();
+
+        let result = client.quorum_loop(in_flight).await;
+        assert!(result.is_ok());
+    }
+}
@@ -0,0 +1,59 @@
+// Copyright 2022 CeresDB Project Authors. Licensed under Apache-2.0.
+
+use std::time::Duration;
+
+use crate::rpc_client::pool::PoolStrategy;
+
+/// Transport-level configuration for the gRPC channels opened to each
+/// endpoint.
+#[derive(Clone, Debug)]
+pub struct RpcConfig {
+    pub keep_alive_while_idle: bool,
+    pub keep_alive_timeout: Duration,
+    pub keep_alive_interval: Duration,
+    /// Number of channels to keep open per endpoint. Must be at least 1.
+    pub channel_pool_size: usize,
+    /// How calls are distributed across the pooled channels.
+    pub channel_pool_strategy: PoolStrategy,
+}
+
+impl Default for RpcConfig {
+    fn default() -> Self {
+        RpcConfig {
+            keep_alive_while_idle: true,
+            keep_alive_timeout: Duration::from_secs(5),
+            keep_alive_interval: Duration::from_secs(60),
+            channel_pool_size: 1,
+            channel_pool_strategy: PoolStrategy::default(),
+        }
+    }
+}
+
+/// Per-call defaults and retry behavior for [`RpcClientImpl`](crate::rpc_client::rpc_client_impl::RpcClientImpl).
+#[derive(Clone, Debug)]
+pub struct RpcOptions {
+    pub connect_timeout: Duration,
+    pub read_timeout: Duration,
+    pub write_timeout: Duration,
+    /// Maximum number of retry attempts for a transient failure, on top of
+    /// the initial attempt.
+    pub max_retries: usize,
+    /// Upper bound of the first retry's backoff; the bound doubles on each
+    /// subsequent attempt up to `max_backoff`, and the actual delay is a
+    /// random value between zero and that bound.
+    pub base_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl Default for RpcOptions {
+    fn default() -> Self {
+        RpcOptions {
+            connect_timeout: Duration::from_secs(3),
+            read_timeout: Duration::from_secs(60),
+            write_timeout: Duration::from_secs(10),
+            max_retries: 1,
+            base_backoff: Duration::from_millis(50),
+            max_backoff: Duration::from_secs(1),
+        }
+    }
+}
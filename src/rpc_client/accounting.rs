@@ -0,0 +1,88 @@
+// Copyright 2022 CeresDB Project Authors. Licensed under Apache-2.0.
+
+use std::time::Duration;
+
+/// The kind of RPC a [`Metrics`] observation was recorded for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RequestKind {
+    Query,
+    Write,
+    Route,
+}
+
+/// Per-tenant accounting hook, invoked by [`RpcClientImpl`](super::rpc_client_impl::RpcClientImpl)
+/// right after `check_status` on every `query`, `write` and `route` call;
+/// `query_stream` records nothing. Implement this to feed a metrics backend
+/// such as Prometheus.
+pub trait Metrics: Send + Sync {
+    /// Called once per successful RPC.
+    fn observe_request(
+        &self,
+        tenant: &str,
+        kind: RequestKind,
+        latency: Duration,
+        bytes_sent: usize,
+        rows_returned: usize,
+    );
+
+    /// Called once per RPC that came back with a server-reported error
+    /// code, keyed by [`ServerError::code`](crate::errors::ServerError::code).
+    fn observe_error(&self, tenant: &str, kind: RequestKind, code: u32);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::*;
+
+    #[derive(Default)]
+    struct FakeMetrics {
+        requests: Mutex<Vec<(String, RequestKind, usize, usize)>>,
+        errors: Mutex<Vec<(String, RequestKind, u32)>>,
+    }
+
+    impl Metrics for FakeMetrics {
+        fn observe_request(
+            &self,
+            tenant: &str,
+            kind: RequestKind,
+            _latency: Duration,
+            bytes_sent: usize,
+            rows_returned: usize,
+        ) {
+            self.requests
+                .lock()
+                .unwrap()
+                .push((tenant.to_string(), kind, bytes_sent, rows_returned));
+        }
+
+        fn observe_error(&self, tenant: &str, kind: RequestKind, code: u32) {
+            self.errors.lock().unwrap().push((tenant.to_string(), kind, code));
+        }
+    }
+
+    #[test]
+    fn observations_are_keyed_by_the_calling_tenant() {
+        let metrics = FakeMetrics::default();
+
+        metrics.observe_request("tenant-a", RequestKind::Query, Duration::from_millis(5), 0, 10);
+        metrics.observe_request("tenant-b", RequestKind::Write, Duration::from_millis(3), 128, 0);
+        metrics.observe_error("tenant-a", RequestKind::Query, 42);
+
+        let requests = metrics.requests.lock().unwrap();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[0], ("tenant-a".to_string(), RequestKind::Query, 0, 10));
+        assert_eq!(requests[1], ("tenant-b".to_string(), RequestKind::Write, 128, 0));
+
+        let errors = metrics.errors.lock().unwrap();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0], ("tenant-a".to_string(), RequestKind::Query, 42));
+    }
+
+    #[test]
+    fn request_kinds_of_the_same_variant_are_equal() {
+        assert_eq!(RequestKind::Query, RequestKind::Query);
+        assert_ne!(RequestKind::Query, RequestKind::Write);
+    }
+}
@@ -0,0 +1,131 @@
+// Copyright 2022 CeresDB Project Authors. Licensed under Apache-2.0.
+
+use ceresdbproto::storage::{
+    storage_service_client::StorageServiceClient, AuthenticateRequest as AuthenticateRequestPb,
+};
+use tonic::{
+    metadata::{Ascii, MetadataValue},
+    transport::Channel,
+};
+
+use crate::errors::{AuthCode, AuthFailStatus, Error, Result, ServerError};
+
+/// Credentials used to obtain a token via the `Authenticate` RPC, modeled
+/// on etcd's username/password authenticate flow.
+#[derive(Clone, Debug)]
+pub struct Credentials {
+    pub user: String,
+    pub password: String,
+}
+
+/// A token shared between the token-expiry refresh and every
+/// [`AuthInterceptor`](super::rpc_client_impl::AuthInterceptor) built for a
+/// given [`RpcClientImpl`](super::rpc_client_impl::RpcClientImpl), so a
+/// refresh is picked up by every call that builds its interceptor afterwards
+/// without needing to rebuild the client. An interceptor copies the token
+/// when it is built, so calls already in flight keep the old token.
+pub(crate) struct TokenStore {
+    token: tokio::sync::RwLock<MetadataValue<Ascii>>,
+}
+
+impl TokenStore {
+    /// A store with no token yet, used when the caller has not configured
+    /// [`Credentials`] and only relies on `ctx.token` passed per call.
+    pub fn empty() -> Self {
+        TokenStore {
+            token: tokio::sync::RwLock::new(MetadataValue::from_static("")),
+        }
+    }
+
+    pub async fn get(&self) -> MetadataValue<Ascii> {
+        self.token.read().await.clone()
+    }
+
+    pub async fn set(&self, token: MetadataValue<Ascii>) {
+        *self.token.write().await = token;
+    }
+}
+
+/// Calls the `Authenticate` RPC with `credentials` and returns the token
+/// the server hands back, ready to be attached as grpc metadata.
+pub(crate) async fn authenticate(
+    channel: Channel,
+    credentials: &Credentials,
+) -> Result<MetadataValue<Ascii>> {
+    let mut client = StorageServiceClient::<Channel>::new(channel);
+
+    let req = AuthenticateRequestPb {
+        user: credentials.user.clone(),
+        password: credentials.password.clone(),
+    };
+
+    let resp = client
+        .authenticate(req)
+        .await
+        .map_err(Error::Rpc)?
+        .into_inner();
+
+    if let Some(header) = resp.header.as_ref() {
+        if !crate::util::is_ok(header.code) {
+            return Err(Error::AuthFail(AuthFailStatus {
+                code: AuthCode::Unauthenticated,
+                msg: header.error.clone(),
+            }));
+        }
+    }
+
+    resp.token.parse().map_err(|_e| {
+        Error::AuthFail(AuthFailStatus {
+            code: AuthCode::InvalidTokenMeta,
+            msg: format!(
+                "invalid token returned by Authenticate: {}, can not be converted to grpc metadata",
+                resp.token
+            ),
+        })
+    })
+}
+
+/// Whether a [`ServerError`] reports that the caller's token has expired
+/// and a refresh-and-retry is worth attempting.
+pub(crate) fn is_token_expired(err: &ServerError) -> bool {
+    err.code == AuthCode::TokenExpired as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn empty_store_has_no_token() {
+        let store = TokenStore::empty();
+        assert_eq!(store.get().await, MetadataValue::from_static(""));
+    }
+
+    #[tokio::test]
+    async fn set_replaces_the_stored_token_for_subsequent_gets() {
+        let store = TokenStore::empty();
+        let token: MetadataValue<Ascii> = "refreshed-token".parse().unwrap();
+
+        store.set(token.clone()).await;
+
+        assert_eq!(store.get().await, token);
+    }
+
+    #[test]
+    fn token_expired_code_is_recognized() {
+        let err = ServerError {
+            code: AuthCode::TokenExpired as u32,
+            msg: "token expired".to_string(),
+        };
+        assert!(is_token_expired(&err));
+    }
+
+    #[test]
+    fn other_error_codes_are_not_token_expiry() {
+        let err = ServerError {
+            code: AuthCode::Unauthenticated as u32,
+            msg: "bad credentials".to_string(),
+        };
+        assert!(!is_token_expired(&err));
+    }
+}
@@ -0,0 +1,256 @@
+// Copyright 2022 CeresDB Project Authors. Licensed under Apache-2.0.
+
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, RwLock,
+    },
+};
+
+use tonic::transport::{Channel, Endpoint};
+
+/// How [`ChannelPool`] picks a channel for the next call.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum PoolStrategy {
+    #[default]
+    RoundRobin,
+    LeastOutstanding,
+}
+
+struct Slot {
+    channel: RwLock<Channel>,
+    outstanding: AtomicUsize,
+    /// Set while a reconnect for this slot is in flight, so a second
+    /// caller reporting the same broken channel doesn't spawn a second
+    /// redundant reconnect.
+    rebuilding: AtomicBool,
+}
+
+/// A small pool of `Channel`s to the same endpoint, so concurrent
+/// high-QPS traffic isn't serialized over a single HTTP/2 connection.
+/// `Channel` is itself cheap to clone (it's a handle), so the pool just
+/// hands out clones of whichever underlying channel it selects. A slot
+/// reported broken via [`ChannelLease::report_broken`] is reconnected in
+/// the background and swapped in once the new channel is ready.
+pub(crate) struct ChannelPool {
+    endpoint: Endpoint,
+    strategy: PoolStrategy,
+    slots: Vec<Slot>,
+    next: AtomicUsize,
+}
+
+/// A channel leased from the pool. For [`PoolStrategy::LeastOutstanding`]
+/// the slot's outstanding-call count is decremented when this is dropped.
+pub(crate) struct ChannelLease {
+    pub channel: Channel,
+    idx: usize,
+    pool: Arc<ChannelPool>,
+    counts_towards_outstanding: bool,
+}
+
+impl ChannelLease {
+    /// Reports that `channel` turned out to be broken (e.g. the caller saw
+    /// a connect-level transport error using it), triggering a background
+    /// reconnect of that slot.
+    pub fn report_broken(&self) {
+        self.pool.clone().rebuild_slot(self.idx);
+    }
+}
+
+impl Drop for ChannelLease {
+    fn drop(&mut self) {
+        if self.counts_towards_outstanding {
+            self.pool.slots[self.idx]
+                .outstanding
+                .fetch_sub(1, Ordering::Relaxed);
+        }
+    }
+}
+
+impl ChannelPool {
+    fn new(endpoint: Endpoint, channels: Vec<Channel>, strategy: PoolStrategy) -> Arc<Self> {
+        Arc::new(ChannelPool {
+            endpoint,
+            strategy,
+            slots: channels
+                .into_iter()
+                .map(|channel| Slot {
+                    channel: RwLock::new(channel),
+                    outstanding: AtomicUsize::new(0),
+                    rebuilding: AtomicBool::new(false),
+                })
+                .collect(),
+            next: AtomicUsize::new(0),
+        })
+    }
+
+    pub fn acquire(self: &Arc<Self>) -> ChannelLease {
+        match self.strategy {
+            PoolStrategy::RoundRobin => {
+                let idx = self.next.fetch_add(1, Ordering::Relaxed) % self.slots.len();
+                ChannelLease {
+                    channel: self.slot_channel(idx),
+                    idx,
+                    pool: self.clone(),
+                    counts_towards_outstanding: false,
+                }
+            }
+            PoolStrategy::LeastOutstanding => {
+                let (idx, slot) = self
+                    .slots
+                    .iter()
+                    .enumerate()
+                    .min_by_key(|(_, slot)| slot.outstanding.load(Ordering::Relaxed))
+                    .expect("channel pool is never empty, build_pool rejects size 0");
+                slot.outstanding.fetch_add(1, Ordering::Relaxed);
+                ChannelLease {
+                    channel: self.slot_channel(idx),
+                    idx,
+                    pool: self.clone(),
+                    counts_towards_outstanding: true,
+                }
+            }
+        }
+    }
+
+    fn slot_channel(&self, idx: usize) -> Channel {
+        self.slots[idx]
+            .channel
+            .read()
+            .expect("channel pool lock poisoned")
+            .clone()
+    }
+
+    /// Reconnects the channel at `idx` in the background and swaps it in
+    /// once ready, unless a reconnect for that slot is already underway.
+    fn rebuild_slot(self: Arc<Self>, idx: usize) {
+        if self.slots[idx].rebuilding.swap(true, Ordering::AcqRel) {
+            return;
+        }
+
+        tokio::spawn(async move {
+            if let Ok(channel) = self.endpoint.connect().await {
+                *self.slots[idx]
+                    .channel
+                    .write()
+                    .expect("channel pool lock poisoned") = channel;
+            }
+            self.slots[idx].rebuilding.store(false, Ordering::Release);
+        });
+    }
+}
+
+/// Error building a [`ChannelPool`], wrapped by the caller into
+/// [`Error::Connect`](crate::errors::Error::Connect) alongside the
+/// endpoint address.
+#[derive(Debug)]
+pub(crate) enum PoolError {
+    /// `channel_pool_size` was configured as 0; a pool needs at least one
+    /// channel to ever hand out.
+    InvalidSize,
+    Connect(tonic::transport::Error),
+}
+
+impl fmt::Display for PoolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PoolError::InvalidSize => write!(f, "channel pool size must be at least 1"),
+            PoolError::Connect(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::error::Error for PoolError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            PoolError::InvalidSize => None,
+            PoolError::Connect(e) => Some(e),
+        }
+    }
+}
+
+/// Builds a pool of `size` channels to the same configured endpoint.
+/// Returns [`PoolError::InvalidSize`] rather than building (and later
+/// panicking on) an empty pool.
+pub(crate) async fn build_pool(
+    configured_endpoint: Endpoint,
+    size: usize,
+    strategy: PoolStrategy,
+) -> Result<Arc<ChannelPool>, PoolError> {
+    if size == 0 {
+        return Err(PoolError::InvalidSize);
+    }
+
+    let mut channels = Vec::with_capacity(size);
+    for _ in 0..size {
+        channels.push(
+            configured_endpoint
+                .connect()
+                .await
+                .map_err(PoolError::Connect)?,
+        );
+    }
+    Ok(ChannelPool::new(configured_endpoint, channels, strategy))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_channel() -> Channel {
+        Endpoint::from_static("http://127.0.0.1:1").connect_lazy()
+    }
+
+    fn pool_of(n: usize, strategy: PoolStrategy) -> Arc<ChannelPool> {
+        ChannelPool::new(
+            Endpoint::from_static("http://127.0.0.1:1"),
+            (0..n).map(|_| test_channel()).collect(),
+            strategy,
+        )
+    }
+
+    #[test]
+    fn round_robin_cycles_through_every_slot() {
+        let pool = pool_of(3, PoolStrategy::RoundRobin);
+        let mut seen = std::collections::HashSet::new();
+        for _ in 0..6 {
+            seen.insert(pool.acquire().idx);
+        }
+        assert_eq!(seen, (0..3).collect());
+    }
+
+    #[test]
+    fn least_outstanding_prefers_idle_slot() {
+        let pool = pool_of(2, PoolStrategy::LeastOutstanding);
+        let busy = pool.acquire();
+        // The other slot has zero outstanding calls, so it should be picked
+        // every time while `busy` is held.
+        for _ in 0..4 {
+            let lease = pool.acquire();
+            assert_ne!(lease.idx, busy.idx);
+        }
+    }
+
+    #[test]
+    fn least_outstanding_releases_on_drop() {
+        let pool = pool_of(1, PoolStrategy::LeastOutstanding);
+        {
+            let _lease = pool.acquire();
+            assert_eq!(pool.slots[0].outstanding.load(Ordering::Relaxed), 1);
+        }
+        assert_eq!(pool.slots[0].outstanding.load(Ordering::Relaxed), 0);
+    }
+
+    #[tokio::test]
+    async fn invalid_size_is_rejected() {
+        let err = build_pool(
+            Endpoint::from_static("http://127.0.0.1:1"),
+            0,
+            PoolStrategy::RoundRobin,
+        )
+        .await
+        .expect_err("pool size 0 must be rejected");
+        assert!(matches!(err, PoolError::InvalidSize));
+    }
+}
@@ -0,0 +1,104 @@
+// Copyright 2022 CeresDB Project Authors. Licensed under Apache-2.0.
+
+use std::time::Duration;
+
+use rand::Rng;
+use tonic::Code;
+
+use crate::errors::Error;
+
+/// Retry policy for [`RpcClientImpl::query`](super::rpc_client_impl::RpcClientImpl)
+/// and [`RpcClientImpl::write`](super::rpc_client_impl::RpcClientImpl),
+/// applied only to transient failures (see [`is_transient`]).
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    pub max_retries: usize,
+    pub base_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl RetryPolicy {
+    /// Exponential backoff with full jitter for the given zero-based retry
+    /// attempt: `random(0, min(max_backoff, base_backoff * 2^attempt))`.
+    pub(crate) fn backoff(&self, attempt: usize) -> Duration {
+        let exp = self
+            .base_backoff
+            .saturating_mul(1u32.checked_shl(attempt as u32).unwrap_or(u32::MAX));
+        let capped = exp.min(self.max_backoff);
+
+        let jitter_ms = rand::thread_rng().gen_range(0..=capped.as_millis().max(1) as u64);
+        Duration::from_millis(jitter_ms)
+    }
+}
+
+/// Whether `err` is worth retrying: connect failures and the `Unavailable`
+/// / `DeadlineExceeded` gRPC statuses are assumed transient, while a
+/// definitive server error code or an auth failure is not.
+pub(crate) fn is_transient(err: &Error) -> bool {
+    match err {
+        Error::Connect { .. } => true,
+        Error::Rpc(status) => matches!(status.code(), Code::Unavailable | Code::DeadlineExceeded),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tonic::Status;
+
+    use super::*;
+
+    #[derive(Debug)]
+    struct FakeConnectError;
+
+    impl std::fmt::Display for FakeConnectError {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(f, "connect failed")
+        }
+    }
+
+    impl std::error::Error for FakeConnectError {}
+
+    fn policy() -> RetryPolicy {
+        RetryPolicy {
+            max_retries: 3,
+            base_backoff: Duration::from_millis(10),
+            max_backoff: Duration::from_millis(100),
+        }
+    }
+
+    #[test]
+    fn backoff_is_capped_at_max_backoff() {
+        let policy = policy();
+        for attempt in 0..10 {
+            assert!(policy.backoff(attempt) <= policy.max_backoff);
+        }
+    }
+
+    #[test]
+    fn backoff_for_first_attempt_is_bounded_by_base_backoff() {
+        let policy = policy();
+        assert!(policy.backoff(0) <= policy.base_backoff);
+    }
+
+    #[test]
+    fn connect_errors_are_transient() {
+        let err = Error::Connect {
+            addr: "127.0.0.1:1".to_string(),
+            source: Box::new(FakeConnectError),
+        };
+        assert!(is_transient(&err));
+    }
+
+    #[test]
+    fn unavailable_and_deadline_exceeded_are_transient() {
+        assert!(is_transient(&Error::Rpc(Status::unavailable("down"))));
+        assert!(is_transient(&Error::Rpc(Status::deadline_exceeded("slow"))));
+    }
+
+    #[test]
+    fn other_rpc_codes_are_not_transient() {
+        assert!(!is_transient(&Error::Rpc(Status::not_found("missing"))));
+        assert!(!is_transient(&Error::Rpc(Status::invalid_argument("bad"))));
+    }
+}
@@ -1,4 +1,8 @@
-use std::{sync::Arc, time::Duration};
+use std::{
+    pin::Pin,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 use async_trait::async_trait;
 use ceresdbproto::{
@@ -10,6 +14,7 @@ use ceresdbproto::{
         WriteResponse as WriteResponsePb,
     },
 };
+use futures::stream::{Stream, StreamExt};
 use tonic::{
     metadata::{Ascii, MetadataValue},
     service::Interceptor,
@@ -20,29 +25,95 @@ use tonic::{
 use crate::{
     errors::{AuthCode, AuthFailStatus, Error, Result, ServerError},
     options::{RpcConfig, RpcOptions},
-    rpc_client::{RpcClient, RpcClientFactory, RpcContext},
+    rpc_client::{
+        accounting::{Metrics, RequestKind},
+        auth::{self, Credentials, TokenStore},
+        pool::{self, ChannelLease, ChannelPool},
+        retry::{self, RetryPolicy},
+        RpcClient, RpcClientFactory, RpcContext,
+    },
     util::is_ok,
 };
 
+/// A stream of query response chunks, yielded as the server produces them.
+///
+/// The stream ends with an error as soon as any chunk's [`ResponseHeader`]
+/// reports a non-ok status; no further chunks are read after that.
+pub type QueryResponseStream = Pin<Box<dyn Stream<Item = Result<QueryResponsePb>> + Send>>;
+
 struct RpcClientImpl {
-    channel: Channel,
+    pool: Arc<ChannelPool>,
     default_read_timeout: Duration,
     default_write_timeout: Duration,
+    token_store: Arc<TokenStore>,
+    credentials: Option<Credentials>,
+    metrics: Option<Arc<dyn Metrics>>,
+    retry_policy: RetryPolicy,
 }
 
 impl RpcClientImpl {
     fn new(
-        channel: Channel,
+        pool: Arc<ChannelPool>,
         default_read_timeout: Duration,
         default_write_timeout: Duration,
+        token_store: Arc<TokenStore>,
+        credentials: Option<Credentials>,
+        metrics: Option<Arc<dyn Metrics>>,
+        retry_policy: RetryPolicy,
     ) -> Self {
         Self {
-            channel,
+            pool,
             default_read_timeout,
             default_write_timeout,
+            token_store,
+            credentials,
+            metrics,
+            retry_policy,
+        }
+    }
+
+    /// Records a successful call's accounting, keyed by `ctx.tenant` the
+    /// same way auth already partitions by tenant.
+    fn record_request(
+        &self,
+        ctx: &RpcContext,
+        kind: RequestKind,
+        started_at: Instant,
+        bytes_sent: usize,
+        rows_returned: usize,
+    ) {
+        if let Some(metrics) = &self.metrics {
+            metrics.observe_request(&ctx.tenant, kind, started_at.elapsed(), bytes_sent, rows_returned);
         }
     }
 
+    /// Records a server-reported error, keyed by `ServerError::code`.
+    fn record_error(&self, ctx: &RpcContext, kind: RequestKind, code: u32) {
+        if let Some(metrics) = &self.metrics {
+            metrics.observe_error(&ctx.tenant, kind, code);
+        }
+    }
+
+    /// Re-authenticates and caches the fresh token in [`TokenStore`], so
+    /// every interceptor built off it picks up the refreshed token on the
+    /// next call.
+    async fn refresh_token(&self) -> Result<()> {
+        let credentials = self.credentials.as_ref().ok_or_else(|| {
+            Error::AuthFail(AuthFailStatus {
+                code: AuthCode::TokenExpired,
+                msg: "token expired and no credentials configured to refresh it".to_string(),
+            })
+        })?;
+
+        let token = auth::authenticate(self.pool.acquire().channel, credentials).await?;
+        self.token_store.set(token).await;
+        Ok(())
+    }
+
+    async fn build_interceptor(&self, ctx: &RpcContext) -> Result<AuthInterceptor> {
+        AuthInterceptor::new(ctx, self.token_store.clone()).await
+    }
+
     fn check_status(header: ResponseHeader) -> Result<()> {
         if !is_ok(header.code) {
             return Err(Error::Server(ServerError {
@@ -54,6 +125,31 @@ impl RpcClientImpl {
         Ok(())
     }
 
+    /// Adapts a raw gRPC response stream so it ends as soon as any chunk's
+    /// header reports a non-ok status. `unfold` checks the `stopped` flag
+    /// before ever polling `inner` again, so the underlying stream is not
+    /// polled (and no further chunks are read) once that happens.
+    fn terminate_on_error(
+        inner: impl Stream<Item = std::result::Result<QueryResponsePb, Status>> + Unpin + Send + 'static,
+    ) -> impl Stream<Item = Result<QueryResponsePb>> + Send + 'static {
+        futures::stream::unfold((false, inner), |(stopped, mut inner)| async move {
+            if stopped {
+                return None;
+            }
+
+            let chunk = inner.next().await?;
+            let result = chunk.map_err(Error::Rpc).and_then(|mut chunk| {
+                if let Some(header) = chunk.header.take() {
+                    Self::check_status(header)?;
+                }
+                Ok(chunk)
+            });
+
+            let stopped = result.is_err();
+            Some((result, (stopped, inner)))
+        })
+    }
+
     fn make_request<T>(ctx: &RpcContext, req: T, default_timeout: Duration) -> Request<T> {
         let timeout = ctx.timeout.unwrap_or(default_timeout);
         let mut req = Request::new(req);
@@ -73,70 +169,204 @@ impl RpcClientImpl {
 #[async_trait]
 impl RpcClient for RpcClientImpl {
     async fn query(&self, ctx: &RpcContext, req: QueryRequestPb) -> Result<QueryResponsePb> {
-        let interceptor = AuthInterceptor::new(ctx)?;
+        let mut attempt = 0;
+        loop {
+            let result = match self.do_query(ctx, req.clone()).await {
+                Err(Error::Server(e)) if auth::is_token_expired(&e) => {
+                    self.refresh_token().await?;
+                    self.do_query(ctx, req.clone()).await
+                }
+                other => other,
+            };
+
+            match result {
+                Err(e) if attempt < self.retry_policy.max_retries && retry::is_transient(&e) => {
+                    tokio::time::sleep(self.retry_policy.backoff(attempt)).await;
+                    attempt += 1;
+                }
+                other => return other,
+            }
+        }
+    }
+
+    async fn query_stream(
+        &self,
+        ctx: &RpcContext,
+        req: QueryRequestPb,
+    ) -> Result<QueryResponseStream> {
+        let interceptor = self.build_interceptor(ctx).await?;
+        let lease = self.pool.acquire();
         let mut client =
-            StorageServiceClient::<Channel>::with_interceptor(self.channel.clone(), interceptor);
+            StorageServiceClient::<Channel>::with_interceptor(lease.channel.clone(), interceptor);
 
         let resp = client
-            .query(self.make_query_request(ctx, req))
+            .query_stream(self.make_query_request(ctx, req))
             .await
-            .map_err(Error::Rpc)?;
+            .map_err(|e| {
+                report_broken_on_connection_error(&lease, &e);
+                Error::Rpc(e)
+            })?;
+
+        Ok(Box::pin(Self::terminate_on_error(resp.into_inner())))
+    }
+
+    async fn write(&self, ctx: &RpcContext, req: WriteRequestPb) -> Result<WriteResponsePb> {
+        let mut attempt = 0;
+        loop {
+            let result = match self.do_write(ctx, req.clone()).await {
+                Err(Error::Server(e)) if auth::is_token_expired(&e) => {
+                    self.refresh_token().await?;
+                    self.do_write(ctx, req.clone()).await
+                }
+                other => other,
+            };
+
+            match result {
+                Err(e) if attempt < self.retry_policy.max_retries && retry::is_transient(&e) => {
+                    tokio::time::sleep(self.retry_policy.backoff(attempt)).await;
+                    attempt += 1;
+                }
+                other => return other,
+            }
+        }
+    }
+
+    async fn route(&self, ctx: &RpcContext, req: RouteRequestPb) -> Result<RouteResponsePb> {
+        let started_at = Instant::now();
+        let interceptor = self.build_interceptor(ctx).await?;
+        let lease = self.pool.acquire();
+        let mut client =
+            StorageServiceClient::<Channel>::with_interceptor(lease.channel.clone(), interceptor);
+
+        // use the write timeout for the route request.
+        let route_req = Self::make_request(ctx, req, self.default_write_timeout);
+        let resp = client.route(route_req).await.map_err(|e| {
+            report_broken_on_connection_error(&lease, &e);
+            Error::Rpc(e)
+        })?;
         let mut resp = resp.into_inner();
 
         if let Some(header) = resp.header.take() {
-            Self::check_status(header)?;
+            if let Err(e) = Self::check_status(header) {
+                if let Error::Server(ServerError { code, .. }) = &e {
+                    self.record_error(ctx, RequestKind::Route, *code);
+                }
+                return Err(e);
+            }
         }
+        self.record_request(ctx, RequestKind::Route, started_at, 0, resp.routes.len());
 
         Ok(resp)
     }
+}
 
-    async fn write(&self, ctx: &RpcContext, req: WriteRequestPb) -> Result<WriteResponsePb> {
-        let interceptor = AuthInterceptor::new(ctx)?;
+impl RpcClientImpl {
+    async fn do_query(&self, ctx: &RpcContext, req: QueryRequestPb) -> Result<QueryResponsePb> {
+        let started_at = Instant::now();
+        let interceptor = self.build_interceptor(ctx).await?;
+        let lease = self.pool.acquire();
         let mut client =
-            StorageServiceClient::<Channel>::with_interceptor(self.channel.clone(), interceptor);
+            StorageServiceClient::<Channel>::with_interceptor(lease.channel.clone(), interceptor);
 
         let resp = client
-            .write(self.make_write_request(ctx, req))
+            .query(self.make_query_request(ctx, req))
             .await
-            .map_err(Error::Rpc)?;
+            .map_err(|e| {
+                report_broken_on_connection_error(&lease, &e);
+                Error::Rpc(e)
+            })?;
         let mut resp = resp.into_inner();
 
         if let Some(header) = resp.header.take() {
-            Self::check_status(header)?;
+            if let Err(e) = Self::check_status(header) {
+                if let Error::Server(ServerError { code, .. }) = &e {
+                    self.record_error(ctx, RequestKind::Query, *code);
+                }
+                return Err(e);
+            }
         }
+        self.record_request(ctx, RequestKind::Query, started_at, 0, resp.rows.len());
 
         Ok(resp)
     }
 
-    async fn route(&self, ctx: &RpcContext, req: RouteRequestPb) -> Result<RouteResponsePb> {
-        let interceptor = AuthInterceptor::new(ctx)?;
+    async fn do_write(&self, ctx: &RpcContext, req: WriteRequestPb) -> Result<WriteResponsePb> {
+        let started_at = Instant::now();
+        let bytes_sent = prost::Message::encoded_len(&req);
+        let interceptor = self.build_interceptor(ctx).await?;
+        let lease = self.pool.acquire();
         let mut client =
-            StorageServiceClient::<Channel>::with_interceptor(self.channel.clone(), interceptor);
+            StorageServiceClient::<Channel>::with_interceptor(lease.channel.clone(), interceptor);
 
-        // use the write timeout for the route request.
-        let route_req = Self::make_request(ctx, req, self.default_write_timeout);
-        let resp = client.route(route_req).await.map_err(Error::Rpc)?;
+        let resp = client
+            .write(self.make_write_request(ctx, req))
+            .await
+            .map_err(|e| {
+                report_broken_on_connection_error(&lease, &e);
+                Error::Rpc(e)
+            })?;
         let mut resp = resp.into_inner();
 
         if let Some(header) = resp.header.take() {
-            Self::check_status(header)?;
+            if let Err(e) = Self::check_status(header) {
+                if let Error::Server(ServerError { code, .. }) = &e {
+                    self.record_error(ctx, RequestKind::Write, *code);
+                }
+                return Err(e);
+            }
         }
+        self.record_request(
+            ctx,
+            RequestKind::Write,
+            started_at,
+            bytes_sent,
+            resp.affected_rows as usize,
+        );
 
         Ok(resp)
     }
 }
 
+/// Reports `lease`'s channel as broken if `status` indicates a connect-level
+/// failure rather than a server-side error, so the pool reconnects it in the
+/// background instead of handing out the same dead channel to every
+/// subsequent call.
+fn report_broken_on_connection_error(lease: &ChannelLease, status: &Status) {
+    if matches!(status.code(), tonic::Code::Unavailable) {
+        lease.report_broken();
+    }
+}
+
 const RPC_HEADER_TENANT_KEY: &str = "x-ceresdb-access-tenant";
+const RPC_HEADER_TOKEN_KEY: &str = "x-ceresdb-access-token";
 
 /// AuthInterceptor is implemented as an interceptor for tonic.
 /// Its duty is to check user authentication.
 pub struct AuthInterceptor {
     tenant: MetadataValue<Ascii>,
-    _token: MetadataValue<Ascii>,
+    token: MetadataValue<Ascii>,
 }
 
 impl AuthInterceptor {
-    fn new(ctx: &RpcContext) -> std::result::Result<Self, Error> {
+    /// Builds an interceptor for a single call. The token is read from the
+    /// shared [`TokenStore`] rather than `ctx.token` alone, so a refresh
+    /// triggered by a different call is picked up too;
+    /// `ctx.token` is still honored as a per-call override when non-empty.
+    async fn new(ctx: &RpcContext, token_store: Arc<TokenStore>) -> std::result::Result<Self, Error> {
+        let token = if ctx.token.is_empty() {
+            token_store.get().await
+        } else {
+            ctx.token.parse().map_err(|_e| {
+                Error::AuthFail(AuthFailStatus {
+                    code: AuthCode::InvalidTokenMeta,
+                    msg: format!(
+                        "invalid token: {}, can not be converted to grpc metadata",
+                        ctx.token
+                    ),
+                })
+            })?
+        };
+
         Ok(AuthInterceptor {
             tenant: ctx.tenant.parse().map_err(|_e| {
                 Error::AuthFail(AuthFailStatus {
@@ -147,15 +377,7 @@ impl AuthInterceptor {
                     ),
                 })
             })?,
-            _token: ctx.token.parse().map_err(|_e| {
-                Error::AuthFail(AuthFailStatus {
-                    code: AuthCode::InvalidTokenMeta,
-                    msg: format!(
-                        "invalid token: {}, can not be converted to grpc metadata",
-                        ctx.token
-                    ),
-                })
-            })?,
+            token,
         })
     }
 }
@@ -168,6 +390,9 @@ impl Interceptor for AuthInterceptor {
         request
             .metadata_mut()
             .insert(RPC_HEADER_TENANT_KEY, self.tenant.clone());
+        request
+            .metadata_mut()
+            .insert(RPC_HEADER_TOKEN_KEY, self.token.clone());
         Ok(request)
     }
 }
@@ -175,13 +400,29 @@ impl Interceptor for AuthInterceptor {
 pub struct RpcClientImplFactory {
     rpc_opts: RpcOptions,
     grpc_config: RpcConfig,
+    credentials: Option<Credentials>,
+    metrics: Option<Arc<dyn Metrics>>,
 }
 
 impl RpcClientImplFactory {
-    pub fn new(grpc_config: RpcConfig, rpc_opts: RpcOptions) -> Self {
+    /// `credentials`, when set, makes every built [`RpcClientImpl`]
+    /// authenticate up front via the `Authenticate` RPC and transparently
+    /// re-authenticate whenever the server reports the token has expired.
+    /// Without credentials, callers are expected to supply a token through
+    /// `RpcContext::token` on each call, as before. `metrics`, when set, is
+    /// shared by every built client so per-tenant accounting is visible
+    /// across all endpoints.
+    pub fn new(
+        grpc_config: RpcConfig,
+        rpc_opts: RpcOptions,
+        credentials: Option<Credentials>,
+        metrics: Option<Arc<dyn Metrics>>,
+    ) -> Self {
         Self {
             rpc_opts,
             grpc_config,
+            credentials,
+            metrics,
         }
     }
 
@@ -212,17 +453,83 @@ impl RpcClientFactory for RpcClientImplFactory {
                 .connect_timeout(self.rpc_opts.connect_timeout)
                 .keep_alive_while_idle(false),
         };
-        let channel = configured_endpoint
-            .connect()
-            .await
-            .map_err(|e| Error::Connect {
-                addr: endpoint,
-                source: Box::new(e),
-            })?;
+        let pool = pool::build_pool(
+            configured_endpoint,
+            self.grpc_config.channel_pool_size,
+            self.grpc_config.channel_pool_strategy,
+        )
+        .await
+        .map_err(|e| Error::Connect {
+            addr: endpoint,
+            source: Box::new(e),
+        })?;
+
+        let token_store = Arc::new(TokenStore::empty());
+        if let Some(credentials) = &self.credentials {
+            let token = auth::authenticate(pool.acquire().channel, credentials).await?;
+            token_store.set(token).await;
+        }
+
         Ok(Arc::new(RpcClientImpl::new(
-            channel,
+            pool,
             self.rpc_opts.read_timeout,
             self.rpc_opts.write_timeout,
+            token_store,
+            self.credentials.clone(),
+            self.metrics.clone(),
+            RetryPolicy {
+                max_retries: self.rpc_opts.max_retries,
+                base_backoff: self.rpc_opts.base_backoff,
+                max_backoff: self.rpc_opts.max_backoff,
+            },
         )))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use futures::stream;
+
+    use super::*;
+
+    fn chunk(code: u32) -> std::result::Result<QueryResponsePb, Status> {
+        Ok(QueryResponsePb {
+            header: Some(ResponseHeader {
+                code,
+                error: String::new(),
+            }),
+            ..Default::default()
+        })
+    }
+
+    #[tokio::test]
+    async fn passes_through_chunks_until_an_error_header() {
+        let raw = vec![chunk(0), chunk(0)];
+        let results: Vec<_> = RpcClientImpl::terminate_on_error(stream::iter(raw))
+            .collect()
+            .await;
+
+        assert_eq!(results.len(), 2);
+        assert!(results.iter().all(|r| r.is_ok()));
+    }
+
+    #[tokio::test]
+    async fn stops_reading_as_soon_as_a_chunk_reports_an_error() {
+        let polled = Arc::new(AtomicUsize::new(0));
+        let polled_in_stream = polled.clone();
+        let raw = vec![chunk(0), chunk(1), chunk(0)];
+        let source = stream::iter(raw).inspect(move |_| {
+            polled_in_stream.fetch_add(1, Ordering::SeqCst);
+        });
+
+        let results: Vec<_> = RpcClientImpl::terminate_on_error(source).collect().await;
+
+        assert_eq!(results.len(), 2);
+        assert!(results[0].is_ok());
+        assert!(results[1].is_err());
+        // The third, non-erroring chunk is never read.
+        assert_eq!(polled.load(Ordering::SeqCst), 2);
+    }
+}